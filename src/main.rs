@@ -17,13 +17,56 @@ pub mod built_info {
 #[derive(Debug, Default)]
 struct CommandLineEvent {}
 
+/// Explicit program/arguments pair, as an alternative to `command_template`.
+/// Each argument is substituted independently and never re-tokenized, which avoids any
+/// word-splitting or quoting hazard when the caller already knows the argument boundaries.
 #[derive(Debug, Deserialize, JsonSchema)]
+pub struct CommandDefinition {
+  program: String,
+  #[serde(default)]
+  args: Vec<String>,
+}
+
+/// A single step of a multi-step `steps` sequence (see [`CommandLineWorkerParameters::steps`]).
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct CommandLineStep {
+  name: Option<String>,
+  command_template: Option<String>,
+  command: Option<CommandDefinition>,
+  exec_dir: Option<String>,
+  /// When true, an unsuccessful exit of this step does not abort the remaining steps.
+  /// A step that cannot be started (unresolvable command, program not in the allowlist,
+  /// spawn error) still aborts the job.
+  continue_on_error: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize, JsonSchema)]
 pub struct CommandLineWorkerParameters {
-  command_template: String,
+  command_template: Option<String>,
+  command: Option<CommandDefinition>,
+  /// Ordered sequence of commands to run instead of a single `command_template`/`command`.
+  /// The job fails as soon as a step fails, unless that step sets `continue_on_error`.
+  steps: Option<Vec<CommandLineStep>>,
   exec_dir: Option<String>,
   #[serde(flatten)]
   parameters: HashMap<String, String>,
+  /// Pre-flight checks run before the command is spawned: a `paths` list of files/directories
+  /// that must exist and be readable, and an optional `allowed_programs` executable allowlist.
   requirements: Option<HashMap<String, Vec<String>>>,
+  /// Regular expression matched against each line of output to extract a 0-100 progression value.
+  /// The first capture group (or, if absent, the whole match) must parse as a number.
+  progress_regex: Option<String>,
+  /// Exit codes other than 0 that should still be considered a successful execution.
+  success_exit_codes: Option<Vec<i32>>,
+  /// Environment variables applied to the spawned process. Values support the same `{key}`
+  /// substitution as the command template.
+  environment: Option<HashMap<String, String>>,
+  /// When true, the spawned process starts with an empty environment instead of inheriting
+  /// the worker's, so only `environment` variables are visible to it.
+  clear_environment: Option<bool>,
+  /// Grace period, in seconds, given to a cancelled process between SIGTERM and SIGKILL.
+  /// Defaults to 5 seconds.
+  kill_timeout: Option<u64>,
 }
 
 impl MessageEvent<CommandLineWorkerParameters> for CommandLineEvent {
@@ -1,11 +1,17 @@
-use crate::CommandLineWorkerParameters;
+use crate::{CommandDefinition, CommandLineStep, CommandLineWorkerParameters};
 use mcai_worker_sdk::{
   job::{JobResult, JobStatus},
-  McaiChannel, MessageError,
+  is_stopped, publish_job_progression, McaiChannel, MessageError,
 };
-use std::collections::HashMap;
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::os::unix::process::ExitStatusExt;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 const COMMAND_TEMPLATE_IDENTIFIER: &str = "command_template";
 const EXECUTION_DIRECTORY_PARAMETER: &str = "exec_dir";
@@ -13,14 +19,36 @@ const EXECUTION_DIRECTORY_PARAMETER: &str = "exec_dir";
 const INTERNAL_PARAM_IDENTIFIERS: [&str; 2] =
   [COMMAND_TEMPLATE_IDENTIFIER, EXECUTION_DIRECTORY_PARAMETER];
 
+// size of the chunks read from the child stdout/stderr pipes
+const READ_BUFFER_SIZE: usize = 8192;
+// only the last megabyte of output is kept for the final job result
+const MAX_OUTPUT_SIZE: usize = 1024 * 1024;
+// how often to poll the McaiChannel for a stop request while draining output
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(200);
+// grace period given to a cancelled process between SIGTERM and SIGKILL, unless overridden
+const DEFAULT_KILL_TIMEOUT_SECS: u64 = 5;
+
 pub fn process(
-  _channel: Option<McaiChannel>,
+  channel: Option<McaiChannel>,
   parameters: CommandLineWorkerParameters,
   job_result: JobResult,
 ) -> Result<JobResult, MessageError> {
-  let command = compile_command_template(parameters.command_template, parameters.parameters);
+  let param_map = parameters.parameters;
+  let progress_regex = parameters
+    .progress_regex
+    .as_ref()
+    .and_then(|pattern| Regex::new(pattern).ok());
+  let environment = compile_environment(parameters.environment, &param_map);
+  let clear_environment = parameters.clear_environment.unwrap_or(false);
+  let success_exit_codes = parameters.success_exit_codes.unwrap_or_default();
+  let requirements = parameters.requirements.unwrap_or_default();
+  let kill_timeout = Duration::from_secs(
+    parameters
+      .kill_timeout
+      .unwrap_or(DEFAULT_KILL_TIMEOUT_SECS),
+  );
 
-  let mut result = launch(command, parameters.exec_dir).map_err(|msg| {
+  check_required_paths(&requirements).map_err(|msg| {
     MessageError::ProcessingError(
       job_result
         .clone()
@@ -29,46 +57,532 @@ pub fn process(
     )
   })?;
 
-  // limit return message size to 1MB
-  result.truncate(1024 * 1024);
+  if let Some(steps) = parameters.steps {
+    return process_steps(
+      channel,
+      steps,
+      parameters.exec_dir,
+      &param_map,
+      &environment,
+      clear_environment,
+      &success_exit_codes,
+      &requirements,
+      progress_regex,
+      kill_timeout,
+      job_result,
+    );
+  }
 
-  Ok(
-    job_result
-      .with_status(JobStatus::Completed)
-      .with_message(&result),
+  let (program, args) =
+    resolve_command(parameters.command, parameters.command_template, &param_map).map_err(
+      |msg| {
+        MessageError::ProcessingError(
+          job_result
+            .clone()
+            .with_status(JobStatus::Error)
+            .with_message(&msg),
+        )
+      },
+    )?;
+
+  check_allowed_program(&requirements, &program).map_err(|msg| {
+    MessageError::ProcessingError(
+      job_result
+        .clone()
+        .with_status(JobStatus::Error)
+        .with_message(&msg),
+    )
+  })?;
+
+  let result = launch(
+    program,
+    args,
+    parameters.exec_dir,
+    environment,
+    clear_environment,
+    channel,
+    job_result.get_job_id(),
+    progress_regex,
+    kill_timeout,
   )
+  .map_err(|msg| {
+    MessageError::ProcessingError(
+      job_result
+        .clone()
+        .with_status(JobStatus::Error)
+        .with_message(&msg),
+    )
+  })?;
+
+  let job_result = job_result
+    .with_parameter("stdout", &result.stdout)
+    .with_parameter("stderr", &result.stderr)
+    .with_parameter("exit_code", result.exit_code)
+    .with_parameter("terminated_by_signal", result.terminated_by_signal);
+
+  if result.cancelled {
+    return Err(MessageError::ProcessingError(
+      job_result
+        .with_status(JobStatus::Stopped)
+        .with_message("job cancelled"),
+    ));
+  }
+
+  if result.is_success(&success_exit_codes) {
+    return Ok(
+      job_result
+        .with_status(JobStatus::Completed)
+        .with_message(&result.stdout),
+    );
+  }
+
+  Err(MessageError::ProcessingError(
+    job_result
+      .with_status(JobStatus::Error)
+      .with_message(&describe_failure(&result)),
+  ))
+}
+
+// either the `command_template` (tokenized) or the structured `command` definition
+fn resolve_command(
+  command: Option<CommandDefinition>,
+  command_template: Option<String>,
+  param_map: &HashMap<String, String>,
+) -> Result<(String, Vec<String>), String> {
+  match command {
+    Some(command) => Ok(build_command_from_definition(command, param_map)),
+    None => {
+      let command_template = command_template
+        .ok_or_else(|| "missing either \"command_template\" or \"command\" parameter".to_string())?;
+      build_command_from_template(&command_template, param_map)
+    }
+  }
+}
+
+fn compile_environment(
+  environment: Option<HashMap<String, String>>,
+  param_map: &HashMap<String, String>,
+) -> HashMap<String, String> {
+  environment
+    .unwrap_or_default()
+    .iter()
+    .map(|(key, value)| (key.clone(), compile_command_template(value, param_map)))
+    .collect()
+}
+
+fn describe_failure(result: &CommandExecutionResult) -> String {
+  match result.terminated_by_signal {
+    Some(signal) => format!("command terminated by signal {}", signal),
+    None => format!(
+      "command exited with code {}",
+      result
+        .exit_code
+        .map(|code| code.to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+    ),
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_steps(
+  channel: Option<McaiChannel>,
+  steps: Vec<CommandLineStep>,
+  default_exec_dir: Option<String>,
+  param_map: &HashMap<String, String>,
+  environment: &HashMap<String, String>,
+  clear_environment: bool,
+  success_exit_codes: &[i32],
+  requirements: &HashMap<String, Vec<String>>,
+  progress_regex: Option<Regex>,
+  kill_timeout: Duration,
+  job_result: JobResult,
+) -> Result<JobResult, MessageError> {
+  let job_id = job_result.get_job_id();
+  let step_count = steps.len();
+  let mut step_results = vec![];
+  let mut failure = None;
+  let mut cancelled = false;
+
+  for (index, step) in steps.into_iter().enumerate() {
+    let step_name = step
+      .name
+      .clone()
+      .unwrap_or_else(|| format!("step_{}", index + 1));
+
+    let _ = publish_job_progression(
+      channel.clone(),
+      job_id,
+      (index * 100 / step_count.max(1)) as u8,
+    );
+
+    let (program, args) = resolve_command(step.command, step.command_template, param_map)
+      .and_then(|(program, args)| {
+        check_allowed_program(requirements, &program)?;
+        Ok((program, args))
+      })
+      .map_err(|msg| format!("{}: {}", step_name, msg))
+      .map_err(|msg| {
+        MessageError::ProcessingError(
+          job_result.clone().with_status(JobStatus::Error).with_message(&msg),
+        )
+      })?;
+
+    let exec_dir = step.exec_dir.or_else(|| default_exec_dir.clone());
+    let continue_on_error = step.continue_on_error.unwrap_or(false);
+
+    let result = launch(
+      program,
+      args,
+      exec_dir,
+      environment.clone(),
+      clear_environment,
+      channel.clone(),
+      job_id,
+      progress_regex.clone(),
+      kill_timeout,
+    )
+    .map_err(|msg| {
+      MessageError::ProcessingError(
+        job_result
+          .clone()
+          .with_status(JobStatus::Error)
+          .with_message(&format!("{}: {}", step_name, msg)),
+      )
+    })?;
+
+    let step_cancelled = result.cancelled;
+    let step_failed = !result.is_success(success_exit_codes);
+    step_results.push(StepExecutionResult {
+      name: step_name.clone(),
+      exit_code: result.exit_code,
+      terminated_by_signal: result.terminated_by_signal,
+      stdout: result.stdout,
+      stderr: result.stderr,
+    });
+
+    if step_cancelled {
+      cancelled = true;
+      break;
+    }
+
+    if step_failed && !continue_on_error {
+      failure = Some(step_name);
+      break;
+    }
+  }
+
+  let _ = publish_job_progression(channel, job_id, 100);
+
+  let job_result = job_result.with_parameter("steps", &step_results);
+
+  if cancelled {
+    return Err(MessageError::ProcessingError(
+      job_result
+        .with_status(JobStatus::Stopped)
+        .with_message("job cancelled"),
+    ));
+  }
+
+  match failure {
+    None => Ok(job_result.with_status(JobStatus::Completed).with_message(
+      &step_results
+        .last()
+        .map(|step| step.stdout.clone())
+        .unwrap_or_default(),
+    )),
+    Some(failed_step) => Err(MessageError::ProcessingError(
+      job_result.with_status(JobStatus::Error).with_message(&format!(
+        "step \"{}\" failed",
+        failed_step
+      )),
+    )),
+  }
 }
 
-fn compile_command_template(
-  command_template: String,
-  param_map: HashMap<String, String>,
-) -> String {
-  let mut compiled_command_template = command_template;
+fn compile_command_template(template: &str, param_map: &HashMap<String, String>) -> String {
+  let mut compiled_template = template.to_string();
   param_map
     .iter()
     .filter(|(key, _value)| !INTERNAL_PARAM_IDENTIFIERS.contains(&key.as_str()))
     .for_each(|(key, value)| {
-      compiled_command_template = compiled_command_template.replace(&format!("{{{}}}", key), value)
+      compiled_template = compiled_template.replace(&format!("{{{}}}", key), value)
     });
-  compiled_command_template
+  compiled_template
+}
+
+fn build_command_from_definition(
+  command: CommandDefinition,
+  param_map: &HashMap<String, String>,
+) -> (String, Vec<String>) {
+  let program = compile_command_template(&command.program, param_map);
+  let args = command
+    .args
+    .iter()
+    .map(|arg| compile_command_template(arg, param_map))
+    .collect();
+  (program, args)
+}
+
+fn build_command_from_template(
+  command_template: &str,
+  param_map: &HashMap<String, String>,
+) -> Result<(String, Vec<String>), String> {
+  let mut tokens = tokenize_command(command_template)?
+    .into_iter()
+    .map(|token| compile_command_template(&token, param_map));
+
+  let program = tokens
+    .next()
+    .ok_or_else(|| "missing executable in the command line template".to_string())?;
+
+  Ok((program, tokens.collect()))
+}
+
+// POSIX-ish tokenizer: splits on unquoted whitespace, honours single/double quotes and
+// backslash escapes, so a quoted path or argument containing spaces survives as one token.
+fn tokenize_command(command: &str) -> Result<Vec<String>, String> {
+  let mut tokens = vec![];
+  let mut current = String::new();
+  let mut has_token = false;
+  let mut in_single_quotes = false;
+  let mut in_double_quotes = false;
+
+  let mut chars = command.chars().peekable();
+  while let Some(character) = chars.next() {
+    match character {
+      '\\' if !in_single_quotes => {
+        if let Some(escaped) = chars.next() {
+          current.push(escaped);
+          has_token = true;
+        }
+      }
+      '\'' if !in_double_quotes => {
+        in_single_quotes = !in_single_quotes;
+        has_token = true;
+      }
+      '"' if !in_single_quotes => {
+        in_double_quotes = !in_double_quotes;
+        has_token = true;
+      }
+      c if c.is_whitespace() && !in_single_quotes && !in_double_quotes => {
+        if has_token {
+          tokens.push(std::mem::take(&mut current));
+          has_token = false;
+        }
+      }
+      c => {
+        current.push(c);
+        has_token = true;
+      }
+    }
+  }
+
+  if in_single_quotes || in_double_quotes {
+    return Err(format!("unterminated quote in command: {}", command));
+  }
+
+  if has_token {
+    tokens.push(current);
+  }
+
+  Ok(tokens)
+}
+
+const REQUIRED_PATHS_KEY: &str = "paths";
+const ALLOWED_PROGRAMS_KEY: &str = "allowed_programs";
+
+fn path_is_readable(path: &str) -> bool {
+  let path = Path::new(path);
+  if path.is_dir() {
+    std::fs::read_dir(path).is_ok()
+  } else {
+    std::fs::File::open(path).is_ok()
+  }
+}
+
+// fails early, before spawning anything, when a declared required path is missing or unreadable
+fn check_required_paths(requirements: &HashMap<String, Vec<String>>) -> Result<(), String> {
+  let missing: Vec<&String> = requirements
+    .get(REQUIRED_PATHS_KEY)
+    .into_iter()
+    .flatten()
+    .filter(|path| !path_is_readable(path))
+    .collect();
+
+  if missing.is_empty() {
+    return Ok(());
+  }
+
+  Err(format!(
+    "missing or unreadable required path(s): {}",
+    missing
+      .iter()
+      .map(|path| path.as_str())
+      .collect::<Vec<_>>()
+      .join(", ")
+  ))
+}
+
+// when `requirements["allowed_programs"]` is set, rejects any program not in that allowlist
+fn check_allowed_program(
+  requirements: &HashMap<String, Vec<String>>,
+  program: &str,
+) -> Result<(), String> {
+  match requirements.get(ALLOWED_PROGRAMS_KEY) {
+    Some(allowed_programs) if !allowed_programs.iter().any(|allowed| allowed == program) => Err(
+      format!("program \"{}\" is not in the allowed_programs allowlist", program),
+    ),
+    _ => Ok(()),
+  }
+}
+
+// keeps only the last `capacity` bytes of the data written to it
+struct RollingBuffer {
+  data: VecDeque<u8>,
+  capacity: usize,
+}
+
+impl RollingBuffer {
+  fn new(capacity: usize) -> Self {
+    RollingBuffer {
+      data: VecDeque::with_capacity(capacity),
+      capacity,
+    }
+  }
+
+  fn append(&mut self, chunk: &[u8]) {
+    self.data.extend(chunk.iter().copied());
+    while self.data.len() > self.capacity {
+      self.data.pop_front();
+    }
+  }
+
+  fn into_string(self) -> String {
+    let bytes: Vec<u8> = self.data.into_iter().collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+  }
+}
+
+// which pipe a chunk of output was read from
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StreamKind {
+  Stdout,
+  Stderr,
+}
+
+// outcome of running a child process to completion: separate stdout/stderr, and how it ended
+#[derive(Debug, Default, Clone)]
+pub struct CommandExecutionResult {
+  pub stdout: String,
+  pub stderr: String,
+  pub exit_code: Option<i32>,
+  pub terminated_by_signal: Option<i32>,
+  pub cancelled: bool,
+}
+
+impl CommandExecutionResult {
+  fn is_success(&self, success_exit_codes: &[i32]) -> bool {
+    match self.exit_code {
+      Some(0) => true,
+      Some(code) => success_exit_codes.contains(&code),
+      None => false,
+    }
+  }
+}
+
+// outcome of a single step of a `steps` sequence, attached to the final `JobResult`
+#[derive(Debug, Clone, Serialize)]
+struct StepExecutionResult {
+  name: String,
+  exit_code: Option<i32>,
+  terminated_by_signal: Option<i32>,
+  stdout: String,
+  stderr: String,
+}
+
+// reads `stream` in fixed-size chunks, tagging each one with `kind`, until EOF or an error occurs
+fn spawn_stream_reader<R: Read + Send + 'static>(
+  mut stream: R,
+  kind: StreamKind,
+  sender: mpsc::Sender<(StreamKind, Vec<u8>)>,
+) -> thread::JoinHandle<()> {
+  thread::spawn(move || {
+    let mut buffer = [0; READ_BUFFER_SIZE];
+    loop {
+      match stream.read(&mut buffer) {
+        Ok(0) | Err(_) => break,
+        Ok(size) => {
+          if sender.send((kind, buffer[..size].to_vec())).is_err() {
+            break;
+          }
+        }
+      }
+    }
+  })
+}
+
+// extracts a 0-100 progression value from a log line, when `progress_regex` matches it
+fn detect_progress(progress_regex: &Option<Regex>, line: &str) -> Option<u8> {
+  let captures = progress_regex.as_ref()?.captures(line)?;
+  let value = captures.get(1).or_else(|| captures.get(0))?;
+  let percent: f32 = value.as_str().trim().parse().ok()?;
+  Some(percent.clamp(0.0, 100.0) as u8)
 }
 
-fn launch(command: String, exec_dir: Option<String>) -> Result<String, String> {
-  let mut splitted_command: Vec<&str> = command.split(' ').collect();
-  if splitted_command.is_empty() {
-    return Err("missing executable in the command line template".to_string());
+// sends SIGTERM, gives the child `kill_timeout` to exit gracefully, then SIGKILLs it
+fn terminate_child(child: &mut Child, kill_timeout: Duration) -> Result<ExitStatus, String> {
+  unsafe {
+    libc::kill(child.id() as libc::pid_t, libc::SIGTERM);
+  }
+
+  let deadline = Instant::now() + kill_timeout;
+  loop {
+    match child.try_wait() {
+      Ok(Some(status)) => return Ok(status),
+      Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+      Ok(None) => break,
+      Err(error) => return Err(format!("failed to wait for child process: {:?}", error)),
+    }
   }
-  let program = splitted_command.remove(0);
 
-  let mut process = Command::new(program);
+  child
+    .kill()
+    .map_err(|error| format!("failed to kill child process: {:?}", error))?;
+  child
+    .wait()
+    .map_err(|error| format!("failed to wait for killed child process: {:?}", error))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn launch(
+  program: String,
+  args: Vec<String>,
+  exec_dir: Option<String>,
+  environment: HashMap<String, String>,
+  clear_environment: bool,
+  channel: Option<McaiChannel>,
+  job_id: u64,
+  progress_regex: Option<Regex>,
+  kill_timeout: Duration,
+) -> Result<CommandExecutionResult, String> {
+  let command = format!("{} {}", program, args.join(" "));
+
+  let mut process = Command::new(&program);
 
   if let Some(current_dir) = exec_dir {
     process.current_dir(Path::new(&current_dir));
   }
 
-  let output = process
-    .args(splitted_command.as_slice())
-    .output()
+  if clear_environment {
+    process.env_clear();
+  }
+  process.envs(&environment);
+
+  let mut child = process
+    .args(&args)
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
     .map_err(|error| {
       format!(
         "An error occurred process command: {}.\n{:?}",
@@ -76,90 +590,323 @@ fn launch(command: String, exec_dir: Option<String>) -> Result<String, String> {
       )
     })?;
 
-  if output.status.success() {
-    Ok(String::from_utf8(output.stdout).unwrap_or_default())
+  let stdout = child.stdout.take().expect("child stdout was not piped");
+  let stderr = child.stderr.take().expect("child stderr was not piped");
+
+  let (sender, receiver) = mpsc::channel();
+  let stdout_reader = spawn_stream_reader(stdout, StreamKind::Stdout, sender.clone());
+  let stderr_reader = spawn_stream_reader(stderr, StreamKind::Stderr, sender);
+
+  let mut stdout_buffer = RollingBuffer::new(MAX_OUTPUT_SIZE);
+  let mut stderr_buffer = RollingBuffer::new(MAX_OUTPUT_SIZE);
+  let mut stdout_pending_line = String::new();
+  let mut stderr_pending_line = String::new();
+  let mut last_progress = 0;
+  let mut cancelled = false;
+
+  let mut consume = |kind: StreamKind, chunk: Vec<u8>| {
+    let (buffer, pending_line) = match kind {
+      StreamKind::Stdout => (&mut stdout_buffer, &mut stdout_pending_line),
+      StreamKind::Stderr => (&mut stderr_buffer, &mut stderr_pending_line),
+    };
+
+    buffer.append(&chunk);
+
+    pending_line.push_str(&String::from_utf8_lossy(&chunk));
+    // ffmpeg-style progress lines are terminated with '\r', not '\n', so both are
+    // treated as line/record boundaries when scanning for a progress match.
+    while let Some(index) = pending_line.find(|c: char| c == '\n' || c == '\r') {
+      let line: String = pending_line.drain(..=index).collect();
+      if let Some(progress) = detect_progress(&progress_regex, &line) {
+        if progress != last_progress {
+          last_progress = progress;
+          let _ = publish_job_progression(channel.clone(), job_id, progress);
+        }
+      }
+    }
+  };
+
+  let mut last_stop_check = Instant::now();
+
+  loop {
+    match receiver.recv_timeout(STOP_POLL_INTERVAL) {
+      Ok((kind, chunk)) => consume(kind, chunk),
+      Err(mpsc::RecvTimeoutError::Disconnected) => break,
+      Err(mpsc::RecvTimeoutError::Timeout) => {}
+    }
+
+    // Checked on a wall-clock interval rather than only when the channel goes quiet,
+    // so a process that streams output continuously can still be cancelled.
+    if last_stop_check.elapsed() >= STOP_POLL_INTERVAL {
+      last_stop_check = Instant::now();
+      if is_stopped(&channel) {
+        cancelled = true;
+        break;
+      }
+    }
+  }
+
+  let status = if cancelled {
+    terminate_child(&mut child, kill_timeout)?
   } else {
-    let mut message = output.stderr;
-    message.extend(&output.stdout);
-    Err(String::from_utf8(message).unwrap_or_default())
+    child.wait().map_err(|error| {
+      format!(
+        "An error occurred while waiting for command: {}.\n{:?}",
+        command, error
+      )
+    })?
+  };
+
+  stdout_reader.join().ok();
+  stderr_reader.join().ok();
+
+  // drain whatever output the reader threads buffered while the child was being terminated
+  while let Ok((kind, chunk)) = receiver.try_recv() {
+    consume(kind, chunk);
   }
+
+  Ok(CommandExecutionResult {
+    stdout: stdout_buffer.into_string(),
+    stderr: stderr_buffer.into_string(),
+    exit_code: status.code(),
+    terminated_by_signal: status.signal(),
+    cancelled,
+  })
 }
 
 #[test]
 pub fn test_compile_command_template() {
-  let command_template = "ls {option} {path}".to_string();
   let mut parameters = HashMap::new();
   parameters.insert("option".to_string(), "-l".to_string());
   parameters.insert("path".to_string(), ".".to_string());
 
-  let command = compile_command_template(command_template, parameters);
+  let command = compile_command_template("ls {option} {path}", &parameters);
   assert_eq!("ls -l .", command.as_str());
 }
 
 #[test]
 pub fn test_compile_command_template_with_doubles() {
-  let command_template = "ls {option} {path} {option}".to_string();
   let mut parameters = HashMap::new();
   parameters.insert("option".to_string(), "-l".to_string());
   parameters.insert("path".to_string(), ".".to_string());
 
-  let command = compile_command_template(command_template, parameters);
+  let command = compile_command_template("ls {option} {path} {option}", &parameters);
   assert_eq!("ls -l . -l", command.as_str());
 }
 
 #[test]
 pub fn test_compile_command_template_with_fixed_params() {
-  let command_template = "ls {option} {path}".to_string();
   let mut parameters = HashMap::new();
   parameters.insert("option".to_string(), "-l".to_string());
   parameters.insert("path".to_string(), ".".to_string());
   parameters.insert(
     COMMAND_TEMPLATE_IDENTIFIER.to_string(),
-    command_template.clone(),
+    "ls {option} {path}".to_string(),
   );
   parameters.insert(
     EXECUTION_DIRECTORY_PARAMETER.to_string(),
     "/path/to/somewhere".to_string(),
   );
 
-  let command = compile_command_template(command_template, parameters);
+  let command = compile_command_template("ls {option} {path}", &parameters);
   assert_eq!("ls -l .", command.as_str());
 }
 
 #[test]
-pub fn test_launch() {
-  let command = "ls .".to_string();
-  let exec_dir = None;
-  let result = launch(command, exec_dir);
+pub fn test_tokenize_command_with_quoted_spaces() {
+  let tokens = tokenize_command(r#"ffmpeg -i "my file.mp4" -y out.mp4"#).unwrap();
+  assert_eq!(
+    vec!["ffmpeg", "-i", "my file.mp4", "-y", "out.mp4"],
+    tokens
+  );
+}
+
+#[test]
+pub fn test_tokenize_command_with_single_quotes_and_escapes() {
+  let tokens = tokenize_command(r#"echo 'a b' c\ d"#).unwrap();
+  assert_eq!(vec!["echo", "a b", "c d"], tokens);
+}
+
+#[test]
+pub fn test_tokenize_command_unterminated_quote() {
+  assert!(tokenize_command(r#"echo "unterminated"#).is_err());
+}
+
+#[test]
+pub fn test_build_command_from_definition() {
+  let command = CommandDefinition {
+    program: "{program}".to_string(),
+    args: vec!["-i".to_string(), "{input}".to_string()],
+  };
+  let mut parameters = HashMap::new();
+  parameters.insert("program".to_string(), "ffmpeg".to_string());
+  parameters.insert("input".to_string(), "my file.mp4".to_string());
+
+  let (program, args) = build_command_from_definition(command, &parameters);
+  assert_eq!("ffmpeg", program.as_str());
+  assert_eq!(vec!["-i", "my file.mp4"], args);
+}
+
+#[test]
+pub fn test_check_required_paths() {
+  let mut requirements = HashMap::new();
+  requirements.insert(REQUIRED_PATHS_KEY.to_string(), vec!["./src".to_string()]);
+  assert!(check_required_paths(&requirements).is_ok());
+}
+
+#[test]
+pub fn test_check_required_paths_missing() {
+  let mut requirements = HashMap::new();
+  requirements.insert(
+    REQUIRED_PATHS_KEY.to_string(),
+    vec!["./this_path_does_not_exist".to_string()],
+  );
+  let error = check_required_paths(&requirements).unwrap_err();
+  assert!(error.contains("this_path_does_not_exist"));
+}
+
+#[test]
+pub fn test_check_allowed_program() {
+  let mut requirements = HashMap::new();
+  requirements.insert(
+    ALLOWED_PROGRAMS_KEY.to_string(),
+    vec!["ls".to_string(), "echo".to_string()],
+  );
+  assert!(check_allowed_program(&requirements, "ls").is_ok());
+  assert!(check_allowed_program(&requirements, "rm").is_err());
+}
+
+#[cfg(test)]
+fn test_launch(
+  program: &str,
+  args: &[&str],
+  exec_dir: Option<&str>,
+) -> Result<CommandExecutionResult, String> {
+  launch(
+    program.to_string(),
+    args.iter().map(|arg| arg.to_string()).collect(),
+    exec_dir.map(|dir| dir.to_string()),
+    HashMap::new(),
+    false,
+    None,
+    0,
+    None,
+    Duration::from_secs(DEFAULT_KILL_TIMEOUT_SECS),
+  )
+}
+
+#[test]
+pub fn test_launch_ok() {
+  let result = test_launch("ls", &["."], None);
   assert!(result.is_ok());
 
-  let program_output = result.unwrap();
-  assert!(program_output.contains("Cargo.toml"));
-  assert!(program_output.contains("Cargo.lock"));
+  let execution_result = result.unwrap();
+  assert_eq!(Some(0), execution_result.exit_code);
+  assert_eq!(None, execution_result.terminated_by_signal);
+  assert!(execution_result.stdout.contains("Cargo.toml"));
+  assert!(execution_result.stdout.contains("Cargo.lock"));
 }
 
 #[test]
 pub fn test_launch_with_exec_dir() {
-  let command = "ls .".to_string();
-  let exec_dir = Some("./src".to_string());
-  let result = launch(command, exec_dir);
+  let result = test_launch("ls", &["."], Some("./src"));
   assert!(result.is_ok());
 
-  let program_output = result.unwrap();
-  assert!(program_output.contains("main.rs"));
-  assert!(program_output.contains("message.rs"));
+  let execution_result = result.unwrap();
+  assert!(execution_result.stdout.contains("main.rs"));
+  assert!(execution_result.stdout.contains("message.rs"));
 }
 
 #[test]
 pub fn test_launch_error() {
-  let command = "ls sdjqenfdcnekbnbsdvjhqr".to_string();
-  let exec_dir = None;
-  let result = launch(command, exec_dir);
-  assert!(result.is_err());
+  let result = test_launch("ls", &["sdjqenfdcnekbnbsdvjhqr"], None);
+  assert!(result.is_ok());
+
+  let execution_result = result.unwrap();
+  assert_eq!(Some(2), execution_result.exit_code);
+  assert!(!execution_result.is_success(&[]));
+  assert!(execution_result.stderr.contains("ls:"));
+  assert!(execution_result.stderr.contains("sdjqenfdcnekbnbsdvjhqr"));
+}
+
+#[test]
+pub fn test_launch_with_success_exit_codes() {
+  let result = test_launch("ls", &["sdjqenfdcnekbnbsdvjhqr"], None);
+  let execution_result = result.unwrap();
+  assert!(execution_result.is_success(&[2]));
+}
+
+#[test]
+pub fn test_launch_with_progress() {
+  let progress_regex = Regex::new(r"progress:(\d+)").ok();
+  let result = launch(
+    "echo".to_string(),
+    vec!["progress:42".to_string()],
+    None,
+    HashMap::new(),
+    false,
+    None,
+    0,
+    progress_regex,
+    Duration::from_secs(DEFAULT_KILL_TIMEOUT_SECS),
+  );
+  assert!(result.is_ok());
+  assert!(result.unwrap().stdout.contains("progress:42"));
+}
+
+#[test]
+pub fn test_launch_with_environment() {
+  let mut environment = HashMap::new();
+  environment.insert("MCAI_TEST_VAR".to_string(), "hello".to_string());
+
+  let result = launch(
+    "sh".to_string(),
+    vec!["-c".to_string(), "echo $MCAI_TEST_VAR".to_string()],
+    None,
+    environment,
+    false,
+    None,
+    0,
+    None,
+    Duration::from_secs(DEFAULT_KILL_TIMEOUT_SECS),
+  );
+  assert!(result.is_ok());
+  assert!(result.unwrap().stdout.contains("hello"));
+}
 
-  let error_message = result.unwrap_err();
-  assert!(error_message.contains("ls:"));
-  assert!(error_message.contains("sdjqenfdcnekbnbsdvjhqr"));
+#[test]
+pub fn test_launch_with_clear_environment() {
+  let result = launch(
+    "sh".to_string(),
+    vec!["-c".to_string(), "echo $PATH".to_string()],
+    None,
+    HashMap::new(),
+    true,
+    None,
+    0,
+    None,
+    Duration::from_secs(DEFAULT_KILL_TIMEOUT_SECS),
+  );
+  assert!(result.is_ok());
+  assert_eq!("\n", result.unwrap().stdout);
+}
+
+#[test]
+pub fn test_launch_with_cancellation() {
+  let result = launch(
+    "sleep".to_string(),
+    vec!["1".to_string()],
+    None,
+    HashMap::new(),
+    false,
+    None,
+    0,
+    None,
+    Duration::from_millis(100),
+  );
+  // with no channel, `is_stopped` has nothing to poll and the process is never cancelled
+  assert!(result.is_ok());
+  assert!(!result.unwrap().cancelled);
 }
 
 #[test]
@@ -261,6 +1008,41 @@ pub fn test_process_with_requirements() {
   assert!(message_param.unwrap().contains("main.rs"));
 }
 
+#[test]
+pub fn test_process_with_missing_required_path() {
+  let mut requirements = HashMap::new();
+  requirements.insert(
+    REQUIRED_PATHS_KEY.to_string(),
+    vec!["./this_path_does_not_exist".to_string()],
+  );
+
+  let parameters = CommandLineWorkerParameters {
+    command_template: Some("ls .".to_string()),
+    requirements: Some(requirements),
+    ..Default::default()
+  };
+
+  let job_result = JobResult::new(123);
+  let result = process(None, parameters, job_result);
+  assert!(result.is_err());
+}
+
+#[test]
+pub fn test_process_with_disallowed_program() {
+  let mut requirements = HashMap::new();
+  requirements.insert(ALLOWED_PROGRAMS_KEY.to_string(), vec!["echo".to_string()]);
+
+  let parameters = CommandLineWorkerParameters {
+    command_template: Some("ls .".to_string()),
+    requirements: Some(requirements),
+    ..Default::default()
+  };
+
+  let job_result = JobResult::new(123);
+  let result = process(None, parameters, job_result);
+  assert!(result.is_err());
+}
+
 #[test]
 pub fn test_process_with_error() {
   use mcai_worker_sdk::job::Job;
@@ -299,3 +1081,69 @@ pub fn test_process_with_error() {
   assert!(result.is_err());
   let _error = result.unwrap_err();
 }
+
+#[test]
+pub fn test_process_with_steps() {
+  use mcai_worker_sdk::ParametersContainer;
+
+  let parameters = CommandLineWorkerParameters {
+    steps: Some(vec![
+      CommandLineStep {
+        name: Some("list_src".to_string()),
+        command_template: Some("ls .".to_string()),
+        exec_dir: Some("./src".to_string()),
+        ..Default::default()
+      },
+      CommandLineStep {
+        name: Some("echo_done".to_string()),
+        command_template: Some("echo done".to_string()),
+        ..Default::default()
+      },
+    ]),
+    ..Default::default()
+  };
+
+  let job_result = JobResult::new(123);
+  let result = process(None, parameters, job_result);
+
+  assert!(result.is_ok());
+  let job_result = result.unwrap();
+  assert_eq!(&JobStatus::Completed, job_result.get_status());
+  assert!(job_result
+    .get_parameter::<String>("message")
+    .unwrap()
+    .contains("done"));
+}
+
+#[test]
+pub fn test_process_with_steps_stops_on_failure() {
+  use mcai_worker_sdk::ParametersContainer;
+
+  let parameters = CommandLineWorkerParameters {
+    steps: Some(vec![
+      CommandLineStep {
+        name: Some("failing_step".to_string()),
+        command_template: Some("ls sdjqenfdcnekbnbsdvjhqr".to_string()),
+        ..Default::default()
+      },
+      CommandLineStep {
+        name: Some("never_runs".to_string()),
+        command_template: Some("echo done".to_string()),
+        ..Default::default()
+      },
+    ]),
+    ..Default::default()
+  };
+
+  let job_result = JobResult::new(123);
+  let result = process(None, parameters, job_result);
+
+  assert!(result.is_err());
+  let error = result.unwrap_err();
+  match error {
+    MessageError::ProcessingError(job_result) => {
+      assert_eq!(&JobStatus::Error, job_result.get_status());
+    }
+    _ => panic!("expected a ProcessingError"),
+  }
+}